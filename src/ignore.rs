@@ -0,0 +1,156 @@
+// gitignore-style ignore rules used by the recursive directory walk in `main.rs` to skip
+// anything a project's `.gitignore`/`.ignore` files exclude (e.g. a listed `target`).
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// converts a gitignore glob (which may contain '/') into an anchored regex: '*' matches within a path
+// segment, '**' spans segments, '?' matches a single non-separator character, everything else is
+// escaped literally
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut pattern = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                pattern.push_str(".*");
+                i += 2;
+            }
+            '*' => {
+                pattern.push_str("[^/]*");
+                i += 1;
+            }
+            '?' => {
+                pattern.push_str("[^/]");
+                i += 1;
+            }
+            c => {
+                pattern.push_str(&regex::escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+struct IgnoreRule {
+    regex: regex::Regex,
+    negate: bool,
+    dir_only: bool,
+    // whether the rule only applies when matched against the full path relative to `base` (set
+    // by a leading or embedded '/'), rather than against any single path segment
+    anchored: bool,
+    // the directory this rule was loaded from; patterns are relative to it
+    base: Arc<PathBuf>,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str, base: &Arc<PathBuf>) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut pattern = line;
+        let negate = if let Some(stripped) = pattern.strip_prefix('!') {
+            pattern = stripped;
+            true
+        } else {
+            false
+        };
+
+        let leading_slash = pattern.starts_with('/');
+        if leading_slash {
+            pattern = &pattern[1..];
+        }
+
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        // a slash anywhere other than the very end anchors the pattern to `base`, same as git
+        let anchored = leading_slash || pattern.contains('/');
+
+        match regex::Regex::new(&glob_to_regex(pattern)) {
+            Ok(regex) => Some(IgnoreRule {
+                regex,
+                negate,
+                dir_only,
+                anchored,
+                base: base.clone(),
+            }),
+            Err(_) => None,
+        }
+    }
+
+    // returns Some(ignored) if this rule applies to `path`, None if it has nothing to say about it
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        if self.dir_only && !is_dir {
+            return None;
+        }
+
+        let relative = path.strip_prefix(self.base.as_path()).ok()?;
+        let relative = relative.to_str()?.replace('\\', "/");
+
+        let is_match = if self.anchored {
+            self.regex.is_match(&relative)
+        } else {
+            self.regex.is_match(&relative)
+                || relative
+                    .rsplit('/')
+                    .next()
+                    .map_or(false, |segment| self.regex.is_match(segment))
+        };
+
+        if is_match {
+            Some(!self.negate)
+        } else {
+            None
+        }
+    }
+}
+
+// the accumulated set of ignore rules in effect while descending into a directory tree: parent
+// rules stay in effect for children, with each directory's own `.gitignore`/`.ignore` appended
+#[derive(Clone)]
+pub struct IgnoreStack {
+    rules: Vec<Arc<IgnoreRule>>,
+}
+
+impl IgnoreStack {
+    pub fn root() -> IgnoreStack {
+        IgnoreStack { rules: Vec::new() }
+    }
+
+    // returns a new stack with `directory`'s own `.gitignore`/`.ignore` rules appended to this one
+    pub fn extended(&self, directory: &Path) -> IgnoreStack {
+        let base = Arc::new(directory.to_path_buf());
+        let mut rules = self.rules.clone();
+
+        for filename in [".gitignore", ".ignore"] {
+            let ignore_file = directory.join(filename);
+            if let Ok(contents) = std::fs::read_to_string(&ignore_file) {
+                for line in contents.lines() {
+                    if let Some(rule) = IgnoreRule::parse(line, &base) {
+                        rules.push(Arc::new(rule));
+                    }
+                }
+            }
+        }
+
+        IgnoreStack { rules }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if let Some(result) = rule.matches(path, is_dir) {
+                ignored = result; // last matching rule wins
+            }
+        }
+        ignored
+    }
+}
@@ -1,8 +1,271 @@
+mod ignore;
+mod pool;
+
+// every `println!` in this program goes through this lock once files may be processed on
+// multiple threads, so matches from different files can't interleave mid-line
+static PRINT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+macro_rules! out {
+    ( $($args:expr),+ ) => {{
+        let _guard = PRINT_LOCK.lock().unwrap();
+        println!($($args),*);
+    }}
+}
+
 macro_rules! info {
     ( $quiet:expr, $($args:expr),+ ) => {
         if !($quiet) {
-            println!($($args),*);
+            out!($($args),*);
+        }
+    }
+}
+
+// the set of `--flags` characters this program understands; anything else is reported as an
+// unknown flag so typos don't silently do nothing
+const KNOWN_FLAGS: &str = "iwse";
+
+fn unknown_flags(flags: &str) -> Vec<char> {
+    flags
+        .chars()
+        .filter(|c| !KNOWN_FLAGS.contains(*c))
+        .collect()
+}
+
+// escapes `pattern` when `literal` is set, then applies `--flags`: 'i' case insensitive, 'w'
+// wraps the pattern in word boundaries, 's' dot-matches-newline, 'e' disables the multi-line mode
+// that's otherwise on by default (so ^/$ anchor per-line even when a whole file is read into one
+// buffer)
+fn compile_pattern(pattern: &str, literal: bool, flags: &str) -> Result<regex::Regex, regex::Error> {
+    let pattern = if literal {
+        regex::escape(pattern)
+    } else {
+        pattern.to_owned()
+    };
+    let pattern = if flags.contains('w') {
+        format!("\\b{}\\b", pattern)
+    } else {
+        pattern
+    };
+
+    regex::RegexBuilder::new(&pattern)
+        .case_insensitive(flags.contains('i'))
+        .multi_line(!flags.contains('e'))
+        .dot_matches_new_line(flags.contains('s'))
+        .build()
+}
+
+fn build_regex(
+    pattern: &str,
+    multi_line: bool,
+    dot_matches_new_line: bool,
+) -> Result<regex::Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern)
+        .multi_line(multi_line)
+        .dot_matches_new_line(dot_matches_new_line)
+        .build()
+}
+
+// given a byte offset into `text`, returns the 1-indexed line number it falls on
+fn line_number_at(text: &str, offset: usize) -> usize {
+    text.as_bytes()[..offset].iter().filter(|b| **b == b'\n').count() + 1
+}
+
+// writes `line` to `writer`, colorizing every span matched by `text_replacer`'s search expression
+fn write_highlighted<W: std::io::Write + termcolor::WriteColor>(
+    writer: &mut W,
+    text_replacer: &StringReplacer,
+    line: &str,
+) -> std::io::Result<()> {
+    if !text_replacer.has_search() {
+        return write!(writer, "{}", line);
+    }
+
+    let mut last_end = 0;
+    for mat in text_replacer.find_iter(line) {
+        write!(writer, "{}", &line[last_end..mat.start()])?;
+        writer.set_color(
+            termcolor::ColorSpec::new()
+                .set_fg(Some(termcolor::Color::Red))
+                .set_bold(true),
+        )?;
+        write!(writer, "{}", mat.as_str())?;
+        writer.reset()?;
+        last_end = mat.end();
+    }
+    write!(writer, "{}", &line[last_end..])
+}
+
+fn is_hidden(path: &std::path::Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.starts_with('.'))
+}
+
+// expands a single `--exec` placeholder: '' full path, '/' basename, '//' parent dir, '.' path
+// without extension, '/.' basename without extension, or a capture group reference 'N' (0 is the
+// whole match), optionally with an 'N:-default' fallback for a group that didn't participate
+fn render_exec_placeholder(
+    inner: &str,
+    path: &std::path::Path,
+    captures: &Option<regex::Captures>,
+) -> String {
+    match inner {
+        "" => path.to_string_lossy().into_owned(),
+        "/" => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        "//" => path
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        "." => path.with_extension("").to_string_lossy().into_owned(),
+        "/." => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        _ => {
+            let (index, default) = match inner.split_once(":-") {
+                Some((index, default)) => (index, Some(default)),
+                None => (inner, None),
+            };
+
+            match index.parse::<usize>() {
+                Ok(index) => captures
+                    .as_ref()
+                    .and_then(|captures| captures.get(index))
+                    .map(|group| group.as_str().to_owned())
+                    .or_else(|| default.map(str::to_owned))
+                    .unwrap_or_default(),
+                Err(_) => format!("{{{}}}", inner), // not a recognized placeholder, leave as-is
+            }
+        }
+    }
+}
+
+// expands every `{...}` placeholder in a single `--exec` argv token
+fn expand_exec_token(token: &str, path: &std::path::Path, captures: &Option<regex::Captures>) -> String {
+    let mut result = String::new();
+    let mut i = 0;
+    while i < token.len() {
+        if token.as_bytes()[i] == b'{' {
+            if let Some(end) = token[i..].find('}') {
+                let end = i + end;
+                result.push_str(&render_exec_placeholder(&token[i + 1..end], path, captures));
+                i = end + 1;
+                continue;
+            }
+        }
+
+        let ch = token[i..].chars().next().expect("valid UTF-8 token");
+        result.push(ch);
+        i += ch.len_utf8();
+    }
+    result
+}
+
+// turns `\n`, `\t`, `\r`, `\0`, and `\\` in a replacement pattern into the characters they name, so
+// e.g. `-r 'line1\nline2'` inserts an actual newline instead of a backslash and an 'n'. `\xHH`
+// becomes the character U+00HH: a single byte for HH below 80, but for 80 to FF the UTF-8
+// encoding of that character (two bytes), not the raw byte HH
+fn unescape_replacement(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut result = String::with_capacity(pattern.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] == '\\' && i + 1 < chars.len() {
+            match chars[i + 1] {
+                'n' => {
+                    result.push('\n');
+                    i += 2;
+                    continue;
+                }
+                't' => {
+                    result.push('\t');
+                    i += 2;
+                    continue;
+                }
+                'r' => {
+                    result.push('\r');
+                    i += 2;
+                    continue;
+                }
+                '0' => {
+                    result.push('\0');
+                    i += 2;
+                    continue;
+                }
+                '\\' => {
+                    result.push('\\');
+                    i += 2;
+                    continue;
+                }
+                'x' if i + 3 < chars.len() => {
+                    let hex: String = chars[i + 2..i + 4].iter().collect();
+                    if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                        result.push(byte as char);
+                        i += 4;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
         }
+
+        result.push(chars[i]);
+        i += 1;
+    }
+
+    result
+}
+
+// finds every `$name`, `$1`, or `${name}` capture reference in a replacement pattern (`$$` is a
+// literal '$' and is skipped, matching `Regex::replace_all`'s own syntax)
+fn referenced_captures(replacement: &str) -> Vec<String> {
+    let bytes = replacement.as_bytes();
+    let mut references = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+
+        if bytes.get(i + 1) == Some(&b'$') {
+            i += 2;
+            continue;
+        }
+
+        if bytes.get(i + 1) == Some(&b'{') {
+            if let Some(end) = replacement[i + 2..].find('}') {
+                references.push(replacement[i + 2..i + 2 + end].to_owned());
+                i = i + 2 + end + 1;
+                continue;
+            }
+        }
+
+        let mut end = i + 1;
+        while end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+            end += 1;
+        }
+
+        if end > i + 1 {
+            references.push(replacement[i + 1..end].to_owned());
+        }
+        i = end.max(i + 1);
+    }
+
+    references
+}
+
+fn capture_reference_exists(search: &regex::Regex, reference: &str) -> bool {
+    match reference.parse::<usize>() {
+        Ok(index) => index < search.captures_len(),
+        Err(_) => search
+            .capture_names()
+            .any(|name| name == Some(reference)),
     }
 }
 
@@ -15,21 +278,48 @@ impl StringReplacer {
     pub fn new(
         search_expression: Option<regex::Regex>,
         replace_pattern: Option<String>,
+        multi_line: bool,
+        dot_matches_new_line: bool,
+        literal: bool,
     ) -> StringReplacer {
-        // if no search but there is a replace we'll need a basic search
-        if search_expression.is_none() && replace_pattern.is_some() {
-            StringReplacer {
-                search_expression: Some(
-                    regex::Regex::new(".*").expect("Failed to compile simple '.*' expression"),
-                ),
-                replace_pattern,
+        let replace_pattern = replace_pattern.map(|pattern| {
+            if literal {
+                pattern
+            } else {
+                unescape_replacement(&pattern)
             }
+        });
+
+        // if no search but there is a replace we'll need a basic search
+        let search_expression = if search_expression.is_none() && replace_pattern.is_some() {
+            Some(
+                build_regex(".*", multi_line, dot_matches_new_line)
+                    .expect("Failed to compile simple '.*' expression"),
+            )
         } else {
-            StringReplacer {
-                search_expression,
-                replace_pattern,
+            search_expression
+        };
+
+        if let (Some(search), Some(replace)) = (&search_expression, &replace_pattern) {
+            let bad_references: Vec<String> = referenced_captures(replace)
+                .into_iter()
+                .filter(|reference| !capture_reference_exists(search, reference))
+                .collect();
+
+            if !bad_references.is_empty() {
+                eprintln!(
+                    "Replacement {:?} references capture group(s) {} that don't exist in the search expression",
+                    replace,
+                    bad_references.join(", ")
+                );
+                std::process::exit(1);
             }
         }
+
+        StringReplacer {
+            search_expression,
+            replace_pattern,
+        }
     }
 
     pub fn matches(&self, text: &str) -> bool {
@@ -56,6 +346,35 @@ impl StringReplacer {
             None => std::borrow::Cow::from(text),
         }
     }
+
+    // expands a single already-matched `regex::Captures` using the replace pattern, for callers
+    // that need to confirm/apply matches one at a time rather than via `replace_all`
+    fn expand(&self, captures: &regex::Captures) -> Option<String> {
+        self.replace_pattern.as_ref().map(|replace| {
+            let mut expanded = String::new();
+            captures.expand(replace, &mut expanded);
+            expanded
+        })
+    }
+
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> regex::Matches<'r, 't> {
+        self.search_expression
+            .as_ref()
+            .expect("find_iter requires a search expression")
+            .find_iter(text)
+    }
+
+    pub fn captures_at<'t>(&self, text: &'t str, start: usize) -> Option<regex::Captures<'t>> {
+        self.search_expression
+            .as_ref()
+            .and_then(|expression| expression.captures_at(text, start))
+    }
+}
+
+// a single unit of work for the traversal thread pool in `RSRInstance::run`
+enum Job {
+    Dir(std::path::PathBuf, ignore::IgnoreStack),
+    File(std::path::PathBuf),
 }
 
 struct RSRInstance {
@@ -63,6 +382,13 @@ struct RSRInstance {
     text_replacer: StringReplacer,
     prompt: bool,
     quiet: bool,
+    multiline: bool,
+    hidden: bool,
+    no_ignore: bool,
+    exec_template: Option<Vec<String>>,
+    before_context: usize,
+    after_context: usize,
+    color_choice: termcolor::ColorChoice,
 }
 
 impl RSRInstance {
@@ -71,26 +397,86 @@ impl RSRInstance {
         text_replacer: StringReplacer,
         prompt: bool,
         quiet: bool,
+        multiline: bool,
+        hidden: bool,
+        no_ignore: bool,
+        exec_template: Option<Vec<String>>,
+        before_context: usize,
+        after_context: usize,
+        color_choice: termcolor::ColorChoice,
     ) -> RSRInstance {
         RSRInstance {
             filename_replacer,
             text_replacer,
             prompt,
             quiet,
+            multiline,
+            hidden,
+            no_ignore,
+            exec_template,
+            before_context,
+            after_context,
+            color_choice,
         }
     }
 
-    pub fn handle_directory(&self, directory: &std::path::Path) {
+    // walks `directory`, processing files as it finds them. `threads` worker threads pop entries
+    // off a shared queue: directories push their (filtered) children back onto the queue for any
+    // idle worker to pick up, and files are handled directly. Prompting interactively only makes
+    // sense from a single thread, so callers should pass `threads == 1` whenever `self.prompt` is
+    // set.
+    pub fn run(&self, directory: &std::path::Path, threads: usize) {
+        let queue: pool::WorkQueue<Job> = pool::WorkQueue::new();
+        queue.push(Job::Dir(directory.to_path_buf(), ignore::IgnoreStack::root()));
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| {
+                    while let Some(job) = queue.pop() {
+                        match &*job {
+                            Job::Dir(directory, ignore_stack) => {
+                                self.push_directory_children(directory, ignore_stack, &queue)
+                            }
+                            Job::File(file) => self.handle_file(file),
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    // reads `directory`'s immediate children, filters out hidden/ignored entries, and pushes
+    // whatever's left back onto `queue` as new jobs
+    fn push_directory_children(
+        &self,
+        directory: &std::path::Path,
+        ignore_stack: &ignore::IgnoreStack,
+        queue: &pool::WorkQueue<Job>,
+    ) {
+        let ignore_stack = if self.no_ignore {
+            ignore_stack.clone()
+        } else {
+            ignore_stack.extended(directory)
+        };
+
         match directory.read_dir() {
             Ok(iter) => {
                 for entry in iter {
                     if let Ok(entry) = entry {
                         let path = entry.path();
                         if let Ok(file_type) = entry.file_type() {
+                            if !self.hidden && is_hidden(&path) {
+                                continue;
+                            }
+                            if !self.no_ignore && ignore_stack.is_ignored(&path, file_type.is_dir())
+                            {
+                                continue;
+                            }
+
                             if file_type.is_dir() {
-                                self.handle_directory(&path);
+                                queue.push(Job::Dir(path, ignore_stack.clone()));
                             } else {
-                                self.handle_file(&path);
+                                queue.push(Job::File(path));
                             }
                         } else {
                             info!(self.quiet, "Ignored {:?}, could not get file type", path);
@@ -112,12 +498,24 @@ impl RSRInstance {
             if let Some(filename) = filename.to_str() {
                 if self.filename_replacer.matches(&filename) {
                     let mut print_filename = true;
+                    if let Some(template) = &self.exec_template {
+                        print_filename = false; // did something so no need to print filename
+                        self.run_exec(template, file, filename);
+                    }
                     if self.text_replacer.has_replace() {
                         print_filename = false; // did something so no need to print filename
-                        self.replace_file_contents(&filename, &file);
+                        if self.multiline {
+                            self.replace_file_contents_multiline(&filename, &file);
+                        } else {
+                            self.replace_file_contents(&filename, &file);
+                        }
                     } else if self.text_replacer.has_search() {
                         print_filename = false; // did something so no need to print filename
-                        self.search_file_contents(&file);
+                        if self.multiline {
+                            self.search_file_contents_multiline(&file);
+                        } else {
+                            self.search_file_contents(&file);
+                        }
                     }
 
                     // do we need to rename?
@@ -130,7 +528,7 @@ impl RSRInstance {
 
                             if self.confirm(&format!("Rename {:?} => {:?}?", file, new_path)) {
                                 if let Err(e) = std::fs::rename(file, &new_path) {
-                                    println!(
+                                    out!(
                                         "Failed to rename {:?} to {:?}: {}!",
                                         file, new_path, e
                                     );
@@ -155,6 +553,45 @@ impl RSRInstance {
         }
     }
 
+    // line-by-line replace loop shared by `replace_file_contents` and the stdin/stdout filter
+    // mode in `main`: reads `location` (used only for the confirm prompt) through `reader`,
+    // writing each (possibly replaced) line to `writer`
+    fn replace_stream<R: std::io::BufRead, W: std::io::Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        location: &str,
+    ) -> std::io::Result<()> {
+        let mut line_number = 0;
+        loop {
+            line_number += 1; // starts at zero so increment first
+            let mut line = String::new();
+
+            let count = reader.read_line(&mut line)?;
+            // 0 count indicates we've read everything
+            if count == 0 {
+                break;
+            }
+
+            let new_line = self.text_replacer.do_replace(&line);
+            if new_line != line
+                && self.confirm(&format!(
+                    "{}:{}\n\t{}\n\t=>\n\t{}",
+                    location,
+                    line_number,
+                    line.trim(),
+                    new_line.trim()
+                ))
+            {
+                writer.write_all(new_line.as_bytes())?;
+            } else {
+                writer.write_all(line.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn replace_file_contents(&self, input_filename: &str, input_path: &std::path::Path) {
         let mut read_option = std::fs::OpenOptions::new();
         read_option.read(true);
@@ -167,56 +604,23 @@ impl RSRInstance {
 
             match write_option.open(&tmp_file) {
                 Ok(output_file) => {
-                    use std::io::{BufRead, Write};
-
                     let mut reader = std::io::BufReader::new(input_file);
                     let mut writer = std::io::BufWriter::new(output_file);
-                    let mut line_number = 1;
-                    loop {
-                        line_number += 1; // starts at zero so increment first
-                        let mut line = String::new();
-
-                        match reader.read_line(&mut line) {
-                            Ok(count) => {
-                                // 0 count indicates we've read everything
-                                if count == 0 {
-                                    break;
-                                }
-
-                                let new_line = self.text_replacer.do_replace(&line);
-                                let result = if new_line != line
-                                    && self.confirm(&format!(
-                                        "{}:{}\n\t{}\n\t=>\n\t{}",
-                                        input_path.to_string_lossy(),
-                                        line_number,
-                                        line.trim(),
-                                        new_line.trim()
-                                    )) {
-                                    writer.write_all(new_line.as_bytes())
-                                } else {
-                                    writer.write_all(line.as_bytes())
-                                };
 
-                                if let Err(e) = result {
-                                    // this is actually an error, print regardless of quiet level
-                                    println!(
-                                        "Skipping {:?} as not all lines could be written to {:?}: {}",
-                                        input_path, tmp_file, e
-                                    );
-                                    std::fs::remove_file(tmp_file).unwrap_or(()); // we don't care if the remove fails
-                                    return;
-                                }
-                            }
-                            Err(e) => {
-                                // this is actually an error, print regardless of quiet level
-                                println!(
-                                    "Skipping {:?} as not all lines could be read: {}",
-                                    input_path, e
-                                );
-                                std::fs::remove_file(tmp_file).unwrap_or(()); // we don't care if the remove fails
-                                return;
-                            }
-                        }
+                    if let Err(e) = self.replace_stream(
+                        &mut reader,
+                        &mut writer,
+                        &input_path.to_string_lossy(),
+                    ) {
+                        // this is actually an error, print regardless of quiet level
+                        out!(
+                            "Skipping {:?} as not all lines could be read or written: {}",
+                            input_path, e
+                        );
+                        drop(reader);
+                        drop(writer);
+                        std::fs::remove_file(tmp_file).unwrap_or(()); // we don't care if the remove fails
+                        return;
                     }
 
                     // if we got here we've successfully read and written everything, close the files and rename the temp
@@ -227,13 +631,13 @@ impl RSRInstance {
                         if let Err(e) =
                             std::fs::set_permissions(&tmp_file, old_metadata.permissions())
                         {
-                            println!("Failed to match permissions for {:?}, permissions may have changed: {}", input_path, e);
+                            out!("Failed to match permissions for {:?}, permissions may have changed: {}", input_path, e);
                         }
                     }
 
                     if let Err(e) = std::fs::rename(&tmp_file, &input_path) {
                         // this is actually an error, print regardless of quiet level
-                        println!(
+                        out!(
                             "Failed to rename temporary file {:?} to original file {:?}: {}",
                             tmp_file, input_path, e
                         );
@@ -241,7 +645,7 @@ impl RSRInstance {
                 }
                 Err(e) => {
                     // this is actually an error, print regardless of quiet level
-                    println!(
+                    out!(
                         "Skipping {:?} as the the temporary file {:?} could not be opened: {}",
                         input_path, tmp_file, e
                     );
@@ -255,49 +659,182 @@ impl RSRInstance {
         }
     }
 
+    // whole-file counterpart to `replace_file_contents`: reads the entire file into memory so the
+    // search/replace regex can match across line boundaries, then writes the whole buffer back
+    // through the same atomic temp-file-then-rename swap
+    fn replace_file_contents_multiline(&self, input_filename: &str, input_path: &std::path::Path) {
+        let contents = match std::fs::read_to_string(input_path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                info!(
+                    self.quiet,
+                    "Skipping {:?} as the the file could not be read: {}", input_path, e
+                );
+                return;
+            }
+        };
+
+        let mut result = String::with_capacity(contents.len());
+        let mut last_end = 0;
+
+        for mat in self.text_replacer.find_iter(&contents) {
+            result.push_str(&contents[last_end..mat.start()]);
+
+            let replacement = self
+                .text_replacer
+                .captures_at(&contents, mat.start())
+                .and_then(|captures| self.text_replacer.expand(&captures))
+                .unwrap_or_else(|| mat.as_str().to_owned());
+
+            let start_line = line_number_at(&contents, mat.start());
+            let end_line = line_number_at(&contents, mat.end());
+            let location = if start_line == end_line {
+                format!("{}:L{}", input_path.to_string_lossy(), start_line)
+            } else {
+                format!(
+                    "{}:L{}-L{}",
+                    input_path.to_string_lossy(),
+                    start_line,
+                    end_line
+                )
+            };
+
+            if replacement != mat.as_str()
+                && self.confirm(&format!(
+                    "{}\n\t{}\n\t=>\n\t{}",
+                    location,
+                    mat.as_str().trim(),
+                    replacement.trim()
+                ))
+            {
+                result.push_str(&replacement);
+            } else {
+                result.push_str(mat.as_str());
+            }
+
+            last_end = mat.end();
+        }
+        result.push_str(&contents[last_end..]);
+
+        let tmp_file = input_path.with_file_name(input_filename.to_owned() + ".rsr_tmp");
+        if let Err(e) = std::fs::write(&tmp_file, result.as_bytes()) {
+            out!(
+                "Skipping {:?} as the temporary file {:?} could not be written: {}",
+                input_path, tmp_file, e
+            );
+            return;
+        }
+
+        if let Ok(old_metadata) = std::fs::metadata(&input_path) {
+            if let Err(e) = std::fs::set_permissions(&tmp_file, old_metadata.permissions()) {
+                out!(
+                    "Failed to match permissions for {:?}, permissions may have changed: {}",
+                    input_path, e
+                );
+            }
+        }
+
+        if let Err(e) = std::fs::rename(&tmp_file, &input_path) {
+            // this is actually an error, print regardless of quiet level
+            out!(
+                "Failed to rename temporary file {:?} to original file {:?}: {}",
+                tmp_file, input_path, e
+            );
+        }
+    }
+
+    // line-by-line search loop shared by `search_file_contents` and the stdin/stdout filter mode
+    // in `main`: `location` is used as the prefix printed before each matching line. Maintains a
+    // ring buffer of up to `before_context` preceding lines and a countdown of `after_context`
+    // trailing lines around each match, printing a "--" separator between non-contiguous groups
+    fn search_stream<R: std::io::BufRead>(
+        &self,
+        reader: &mut R,
+        location: &str,
+    ) -> std::io::Result<()> {
+        let mut before_buffer: std::collections::VecDeque<(usize, String)> =
+            std::collections::VecDeque::with_capacity(self.before_context + 1);
+        let mut after_remaining = 0;
+        let mut last_printed: Option<usize> = None;
+        let mut line_number = 0;
+
+        loop {
+            line_number += 1; // starts at zero so increment first
+            let mut line = String::new();
+
+            let count = reader.read_line(&mut line)?;
+            // 0 count indicates we've read everything
+            if count == 0 {
+                break;
+            }
+            let line = line.trim_end_matches(['\n', '\r']).to_owned();
+
+            if self.text_replacer.matches(&line) {
+                for (number, content) in &before_buffer {
+                    if last_printed.map_or(true, |last| *number > last) {
+                        self.print_search_line(location, *number, content, &mut last_printed);
+                    }
+                }
+
+                self.print_search_line(location, line_number, &line, &mut last_printed);
+                after_remaining = self.after_context;
+            } else if after_remaining > 0 {
+                self.print_search_line(location, line_number, &line, &mut last_printed);
+                after_remaining -= 1;
+            }
+
+            before_buffer.push_back((line_number, line));
+            if before_buffer.len() > self.before_context {
+                before_buffer.pop_front();
+            }
+        }
+
+        Ok(())
+    }
+
+    // prints one line of search output, colorizing every match span within it, and emits a "--"
+    // separator first if this line isn't contiguous with the last one printed
+    fn print_search_line(
+        &self,
+        location: &str,
+        line_number: usize,
+        line: &str,
+        last_printed: &mut Option<usize>,
+    ) {
+        if self.quiet {
+            return;
+        }
+
+        use std::io::Write;
+
+        let _guard = PRINT_LOCK.lock().unwrap();
+
+        if last_printed.map_or(false, |last| line_number > last + 1) {
+            println!("--");
+        }
+
+        let mut stdout = termcolor::StandardStream::stdout(self.color_choice);
+        let _ = write!(stdout, "{}:{: <8}", location, line_number);
+        let _ = write_highlighted(&mut stdout, &self.text_replacer, line);
+        let _ = writeln!(stdout);
+
+        *last_printed = Some(line_number);
+    }
+
     fn search_file_contents(&self, input_path: &std::path::Path) {
         let mut read_option = std::fs::OpenOptions::new();
         read_option.read(true);
 
         if let Ok(input_file) = read_option.open(&input_path) {
-            use std::io::BufRead;
-
             let mut reader = std::io::BufReader::new(input_file);
-            let mut line_number = 0;
-            loop {
-                line_number += 1; // starts at zero so increment first
-                let mut line = String::new();
-
-                match reader.read_line(&mut line) {
-                    Ok(count) => {
-                        // 0 count indicates we've read everything
-                        if count == 0 {
-                            break;
-                        }
 
-                        if self.text_replacer.matches(&line) {
-                            info!(
-                                self.quiet,
-                                "{}:{: <8}{}",
-                                input_path.to_string_lossy(),
-                                line_number,
-                                line.trim()
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        // this is actually an error, print regardless of quiet level
-                        println!(
-                            "Skipping {:?} as not all lines could be read: {}",
-                            input_path, e
-                        );
-                        return;
-                    }
-                }
+            if let Err(e) = self.search_stream(&mut reader, &input_path.to_string_lossy()) {
+                // this is actually an error, print regardless of quiet level
+                out!(
+                    "Skipping {:?} as not all lines could be read: {}",
+                    input_path, e
+                );
             }
-
-            // if we got here we've successfully read and written everything, close the files and rename the temp
-            drop(reader);
         } else {
             info!(
                 self.quiet,
@@ -306,10 +843,71 @@ impl RSRInstance {
         }
     }
 
+    // whole-file counterpart to `search_file_contents`: matches are reported by line range since a
+    // single match is no longer bound to one line
+    fn search_file_contents_multiline(&self, input_path: &std::path::Path) {
+        let contents = match std::fs::read_to_string(input_path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                info!(
+                    self.quiet,
+                    "Skipping {:?} as the the file could not be read: {}", input_path, e
+                );
+                return;
+            }
+        };
+
+        for mat in self.text_replacer.find_iter(&contents) {
+            let start_line = line_number_at(&contents, mat.start());
+            let end_line = line_number_at(&contents, mat.end());
+            let location = if start_line == end_line {
+                format!("{}:L{}", input_path.to_string_lossy(), start_line)
+            } else {
+                format!(
+                    "{}:L{}-L{}",
+                    input_path.to_string_lossy(),
+                    start_line,
+                    end_line
+                )
+            };
+
+            info!(self.quiet, "{}\t{}", location, mat.as_str().trim());
+        }
+    }
+
+    // runs `template` as a command for `file`, expanding placeholders against `file` and the
+    // capture groups of `filename_replacer`'s regex matched against `filename`
+    fn run_exec(&self, template: &[String], file: &std::path::Path, filename: &str) {
+        let captures = self
+            .filename_replacer
+            .search_expression
+            .as_ref()
+            .and_then(|expression| expression.captures(filename));
+
+        let mut argv = template
+            .iter()
+            .map(|token| expand_exec_token(token, file, &captures));
+
+        let program = match argv.next() {
+            Some(program) => program,
+            None => return,
+        };
+
+        match std::process::Command::new(program).args(argv).status() {
+            Ok(status) if !status.success() => {
+                out!("Command for {:?} exited with {}", file, status);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                out!("Failed to run command for {:?}: {}", file, e);
+            }
+        }
+    }
+
     fn confirm(&self, message: &str) -> bool {
         match self.prompt {
             true => {
-                println!("{} ... Confirm [y/N]: ", message);
+                out!("{} ... Confirm [y/N]: ", message);
                 let mut user_response = String::new();
                 match std::io::stdin().read_line(&mut user_response) {
                     Ok(_) => user_response.trim() == "y",
@@ -350,6 +948,24 @@ fn main() {
             .required(false)
             .help("A replacement pattern to replace any matching text with again <search>. May include references to capture groups, e.g. ${1} or named capture groups like ${name} which would be captured as (?P<name>.*). The curly-brackets are optional but may be required to distinguish between the capture and the rest of the replacement text")
             .takes_value(true))
+        .arg(clap::Arg::with_name("multiline")
+            .short("M")
+            .long("multiline")
+            .required(false)
+            .help("Read each file's full contents into memory and match <search>/<replace> against the whole buffer instead of line-by-line, allowing a pattern to span multiple lines. Combine with --flags=s to additionally let '.' match newlines")
+            .takes_value(false))
+        .arg(clap::Arg::with_name("literal")
+            .short("F")
+            .long("literal")
+            .required(false)
+            .help("Treat <input> and <search> as literal strings rather than regex patterns, escaping any special characters before compiling them")
+            .takes_value(false))
+        .arg(clap::Arg::with_name("flags")
+            .short("f")
+            .long("flags")
+            .required(false)
+            .help("A combination of single-character flags applied to both <input> and <search>: 'i' case-insensitive, 'w' wraps the pattern in word boundaries (\\b), 's' allows '.' to match newline characters, 'e' disables the multi-line mode that's otherwise on by default (so ^/$ stop anchoring per-line)")
+            .takes_value(true))
         .arg(clap::Arg::with_name("prompt")
             .short("p")
             .long("prompt")
@@ -362,21 +978,77 @@ fn main() {
             .required(false)
             .help("If set, supresses any messages that are neither required nor errors")
             .takes_value(false))
+        .arg(clap::Arg::with_name("hidden")
+            .long("hidden")
+            .required(false)
+            .help("If set, dotfiles and dot-directories (e.g. .git) are walked instead of being skipped by default")
+            .takes_value(false))
+        .arg(clap::Arg::with_name("no-ignore")
+            .long("no-ignore")
+            .required(false)
+            .help("If set, .gitignore and .ignore files are not consulted, so ignored files are walked like any other")
+            .takes_value(false))
+        .arg(clap::Arg::with_name("threads")
+            .short("j")
+            .long("threads")
+            .required(false)
+            .help("Number of worker threads to walk the directory tree and process files with (default: available parallelism). Forced to 1 when --prompt is set, since prompting from multiple threads at once is incoherent")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("exec")
+            .short("x")
+            .long("exec")
+            .required(false)
+            .help("A command (with its arguments) to run for each file whose name matches <input>, instead of or in addition to searching/replacing contents. Supports placeholders: {} full path, {/} basename, {//} parent directory, {.} path without extension, {/.} basename without extension, and {N} for the text captured by the N-th group of <input> (0 is the whole match), or {N:-default} to fall back to default when the group didn't participate")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("before")
+            .short("B")
+            .long("before")
+            .required(false)
+            .help("Print this many lines of context before each match, for search mode")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("after")
+            .short("A")
+            .long("after")
+            .required(false)
+            .help("Print this many lines of context after each match, for search mode")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("context")
+            .short("C")
+            .long("context")
+            .required(false)
+            .help("Print this many lines of context both before and after each match, for search mode. Overridden individually by --before/--after")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("color")
+            .long("color")
+            .required(false)
+            .possible_values(&["auto", "always", "never"])
+            .default_value("auto")
+            .help("Whether to colorize the matched span within each line of search output")
+            .takes_value(true))
         .arg(clap::Arg::with_name("dir")
             .required(false)
             .index(1)
-            .help("The directory to search for files within"))
+            .help("The directory to search for files within. If omitted and stdin is not a terminal (or '-' is passed explicitly), rsr instead reads from stdin and writes the result to stdout, applying <search>/<replace> line-by-line with no temp files"))
         .get_matches();
 
-    let dir = match args.value_of("dir") {
-        Some(value) => std::path::Path::new(value),
-        None => std::path::Path::new("."),
-    };
+    use std::io::IsTerminal;
+    let dir_arg = args.value_of("dir");
+    let use_stdin = dir_arg == Some("-") || (dir_arg.is_none() && !std::io::stdin().is_terminal());
+    let dir = std::path::Path::new(dir_arg.unwrap_or("."));
+    let multiline = args.is_present("multiline");
+    let literal = args.is_present("literal");
+    let flags = args.value_of("flags").unwrap_or("");
+    for bad_flag in unknown_flags(flags) {
+        out!("Ignoring unknown --flags character '{}'", bad_flag);
+    }
+    let dot_matches_new_line = flags.contains('s');
+    let flag_multi_line = !flags.contains('e');
+
     let input = match args.value_of("input") {
-        Some(pattern) => match regex::Regex::new(&pattern) {
+        Some(pattern) => match compile_pattern(&pattern, literal, flags) {
             Ok(regex) => Some(regex),
             Err(e) => {
-                println!("Failed to compile regex {}: {}", pattern, e);
+                out!("Failed to compile regex {}: {}", pattern, e);
                 None
             }
         },
@@ -387,10 +1059,10 @@ fn main() {
         None => None,
     };
     let search = match args.value_of("search") {
-        Some(pattern) => match regex::Regex::new(&pattern) {
+        Some(pattern) => match compile_pattern(&pattern, literal, flags) {
             Ok(regex) => Some(regex),
             Err(e) => {
-                println!("Failed to compile regex {}: {}", pattern, e);
+                out!("Failed to compile regex {}: {}", pattern, e);
                 None
             }
         },
@@ -402,11 +1074,118 @@ fn main() {
     };
     let prompt = args.is_present("prompt");
     let quiet = args.is_present("quiet");
+    let hidden = args.is_present("hidden");
+    let no_ignore = args.is_present("no-ignore");
+    let exec_template = args
+        .value_of("exec")
+        .map(|template| template.split_whitespace().map(String::from).collect());
 
-    let filename_replace = StringReplacer::new(input, output);
-    let text_replace = StringReplacer::new(search, replace);
+    let context = args.value_of("context").and_then(|value| match value.parse::<usize>() {
+        Ok(context) => Some(context),
+        Err(e) => {
+            out!("Failed to parse --context value {}: {}", value, e);
+            None
+        }
+    });
+    let before_context = args
+        .value_of("before")
+        .and_then(|value| match value.parse::<usize>() {
+            Ok(before) => Some(before),
+            Err(e) => {
+                out!("Failed to parse --before value {}: {}", value, e);
+                None
+            }
+        })
+        .or(context)
+        .unwrap_or(0);
+    let after_context = args
+        .value_of("after")
+        .and_then(|value| match value.parse::<usize>() {
+            Ok(after) => Some(after),
+            Err(e) => {
+                out!("Failed to parse --after value {}: {}", value, e);
+                None
+            }
+        })
+        .or(context)
+        .unwrap_or(0);
 
-    let instance = RSRInstance::new(filename_replace, text_replace, prompt, quiet);
+    // `possible_values` + `default_value` guarantee this is always one of the three. termcolor's
+    // `Auto` doesn't do TTY detection on its own, so resolve it here: keep colors for an
+    // interactive terminal, but drop them when stdout is redirected to a pipe or file.
+    let color_choice = match args.value_of("color") {
+        Some("always") => termcolor::ColorChoice::Always,
+        Some("never") => termcolor::ColorChoice::Never,
+        _ => {
+            if std::io::stdout().is_terminal() {
+                termcolor::ColorChoice::Auto
+            } else {
+                termcolor::ColorChoice::Never
+            }
+        }
+    };
+
+    // prompting from multiple threads would interleave confirmations incoherently, so force a
+    // single worker regardless of what --threads asked for
+    let threads = if prompt {
+        1
+    } else {
+        match args.value_of("threads") {
+            Some(value) => match value.parse::<usize>() {
+                Ok(threads) => threads,
+                Err(e) => {
+                    out!("Failed to parse --threads value {}: {}", value, e);
+                    1
+                }
+            },
+            None => std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1),
+        }
+    };
 
-    instance.handle_directory(dir);
+    let filename_replace =
+        StringReplacer::new(input, output, flag_multi_line, dot_matches_new_line, literal);
+    let text_replace = StringReplacer::new(
+        search,
+        replace,
+        flag_multi_line || multiline,
+        dot_matches_new_line,
+        literal,
+    );
+
+    let instance = RSRInstance::new(
+        filename_replace,
+        text_replace,
+        prompt,
+        quiet,
+        multiline,
+        hidden,
+        no_ignore,
+        exec_template,
+        before_context,
+        after_context,
+        color_choice,
+    );
+
+    if use_stdin {
+        let stdin = std::io::stdin();
+        let mut reader = std::io::BufReader::new(stdin.lock());
+        let stdout = std::io::stdout();
+        let mut writer = stdout.lock();
+
+        let result = if instance.text_replacer.has_replace() {
+            instance.replace_stream(&mut reader, &mut writer, "<stdin>")
+        } else if instance.text_replacer.has_search() {
+            instance.search_stream(&mut reader, "<stdin>")
+        } else {
+            std::io::copy(&mut reader, &mut writer).map(|_| ())
+        };
+
+        if let Err(e) = result {
+            out!("Error processing stdin: {}", e);
+        }
+    } else {
+        instance.run(dir, threads);
+    }
 }
@@ -0,0 +1,87 @@
+// a shared work queue used to turn the (formerly purely recursive) directory walk into a small
+// work-stealing thread pool: workers pop an item, and if handling it produces more work (e.g. a
+// directory's children) they push it back onto the same queue for any idle worker to pick up
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+struct QueueState<T> {
+    queue: VecDeque<T>,
+    // items that have been pushed but not yet `finish`ed; used to tell "temporarily empty" apart
+    // from "nothing left to do", since a popped directory may still push its children
+    pending: usize,
+}
+
+pub struct WorkQueue<T> {
+    state: Mutex<QueueState<T>>,
+    condvar: Condvar,
+}
+
+impl<T> WorkQueue<T> {
+    pub fn new() -> WorkQueue<T> {
+        WorkQueue {
+            state: Mutex::new(QueueState {
+                queue: VecDeque::new(),
+                pending: 0,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let mut state = self.state.lock().unwrap();
+        state.pending += 1;
+        state.queue.push_back(item);
+        self.condvar.notify_all();
+    }
+
+    // blocks until an item is ready, or returns None once the queue is empty and every
+    // previously popped item has been `finish`ed (so no more work can possibly appear). The
+    // returned guard calls `finish` on drop, including on an unwinding panic, so a job that
+    // panics can't leave `pending` permanently non-zero and wedge every other worker in `pop`.
+    pub fn pop(&self) -> Option<PopGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.queue.pop_front() {
+                return Some(PopGuard {
+                    queue: self,
+                    item,
+                });
+            }
+            if state.pending == 0 {
+                return None;
+            }
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+
+    // marks one previously popped item as fully handled, including anything it may have pushed
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending -= 1;
+        if state.pending == 0 {
+            self.condvar.notify_all();
+        }
+    }
+}
+
+// wraps a popped item so its `WorkQueue::finish` call happens via `Drop`, which runs even if the
+// worker processing the item panics
+pub struct PopGuard<'a, T> {
+    queue: &'a WorkQueue<T>,
+    item: T,
+}
+
+impl<T> std::ops::Deref for PopGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.item
+    }
+}
+
+impl<T> Drop for PopGuard<'_, T> {
+    fn drop(&mut self) {
+        self.queue.finish();
+    }
+}